@@ -0,0 +1,187 @@
+//! Discover and attach existing encrypted block devices into the sandbox.
+
+use std::ffi::CString;
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{fs, ptr};
+
+use rustix::mount::{self, MountFlags, UnmountFlags};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::libcryptsetup;
+use crate::luks;
+use crate::secrets::Secret;
+
+/// A block device discovered via `lsblk`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlockDevice {
+    pub name: PathBuf,
+    pub uuid: Option<String>,
+    pub fstype: Option<String>,
+    pub mountpoint: Option<PathBuf>,
+}
+
+impl BlockDevice {
+    /// Whether this device holds a LUKS container.
+    pub fn is_luks(&self) -> bool {
+        self.fstype.as_deref() == Some("crypto_LUKS")
+    }
+}
+
+#[derive(Deserialize)]
+struct Lsblk {
+    blockdevices: Vec<BlockDevice>,
+}
+
+/// Enumerate all block devices known to the system via `lsblk`.
+pub fn list() -> io::Result<Vec<BlockDevice>> {
+    let output = Command::new("lsblk")
+        .args(["--json", "--paths", "--output", "NAME,UUID,FSTYPE,MOUNTPOINT"])
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other("lsblk failed"));
+    }
+
+    let parsed: Lsblk = serde_json::from_slice(&output.stdout)
+        .map_err(|err| io::Error::other(format!("invalid lsblk output: {err}")))?;
+    Ok(parsed.blockdevices)
+}
+
+/// Find a device by UUID or device path, as passed to `--attach`.
+pub fn find(selector: &str) -> io::Result<Option<BlockDevice>> {
+    let devices = list()?;
+    Ok(devices
+        .into_iter()
+        .find(|device| device.uuid.as_deref() == Some(selector) || device.name == Path::new(selector)))
+}
+
+/// Detect the filesystem type on the unlocked mapped device, so it can be
+/// mounted as what it actually is instead of bind-mounted over its own
+/// `/dev/mapper` entry (which would just expose the block device node, not
+/// its contents).
+fn detect_fstype(mapper_path: &Path) -> io::Result<String> {
+    let output =
+        Command::new("lsblk").args(["--noheadings", "--output", "FSTYPE"]).arg(mapper_path).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other("lsblk failed"));
+    }
+
+    let fstype = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    if fstype.is_empty() {
+        return Err(io::Error::other(format!("could not detect filesystem type on {}", mapper_path.display())));
+    }
+
+    Ok(fstype)
+}
+
+/// An unlocked LUKS container, optionally mounted somewhere inside the
+/// sandbox. Unmounted and deactivated on [`Drop`].
+pub struct AttachedDevice {
+    crypt_device: *mut libcryptsetup::crypt_device,
+    /// Mapped device name with trailing \0.
+    mapped_name: (String, CString),
+    mount_path: Option<PathBuf>,
+}
+
+impl AttachedDevice {
+    /// Unlock `device`'s LUKS container with `passphrase`.
+    pub fn unlock(device: &BlockDevice, passphrase: &Secret) -> Result<Self, crate::Error> {
+        if !device.is_luks() {
+            return Err(Error::NotLuks.into());
+        }
+
+        let c_device_path = CString::new(device.name.as_os_str().as_bytes())?;
+        let passphrase = passphrase.as_cstr();
+
+        let mut crypt_device: MaybeUninit<*mut libcryptsetup::crypt_device> = MaybeUninit::uninit();
+        let result =
+            unsafe { libcryptsetup::crypt_init(crypt_device.as_mut_ptr(), c_device_path.as_ptr()) };
+        if result < 0 {
+            return Err(luks::Error::Init.into());
+        }
+        let crypt_device = unsafe { crypt_device.assume_init() };
+
+        let result =
+            unsafe { libcryptsetup::crypt_load(crypt_device, c"LUKS2".as_ptr(), ptr::null_mut()) };
+        if result < 0 {
+            unsafe { libcryptsetup::crypt_free(crypt_device) };
+            return Err(luks::Error::Load.into());
+        }
+
+        let mapped_name = format!("homesec-attach-{}", Uuid::new_v4());
+        let c_mapped_name = CString::new(mapped_name.as_bytes())?;
+        let result = unsafe {
+            libcryptsetup::crypt_activate_by_passphrase(
+                crypt_device,
+                c_mapped_name.as_ptr(),
+                libcryptsetup::CRYPT_ANY_SLOT,
+                passphrase.as_ptr(),
+                passphrase.count_bytes(),
+                0,
+            )
+        };
+        if result < 0 {
+            unsafe { libcryptsetup::crypt_free(crypt_device) };
+            return Err(luks::Error::Map.into());
+        }
+
+        Ok(Self { crypt_device, mapped_name: (mapped_name, c_mapped_name), mount_path: None })
+    }
+
+    /// Mount the unlocked device at `path`, read-only or read-write.
+    pub fn mount(&mut self, path: impl Into<PathBuf>, read_only: bool) -> Result<(), crate::Error> {
+        let path = path.into();
+        fs::create_dir_all(&path)?;
+
+        let mapper_path = PathBuf::from("/dev/mapper").join(&self.mapped_name.0);
+        let fstype = detect_fstype(&mapper_path)?;
+
+        let flags = if read_only { MountFlags::RDONLY } else { MountFlags::empty() };
+        mount::mount2(Some(mapper_path), &path, Some(fstype.as_str()), flags, None)?;
+
+        self.mount_path = Some(path);
+        Ok(())
+    }
+
+    /// Unmount the device, if it is currently mounted.
+    pub fn unmount(&mut self) -> Result<(), crate::Error> {
+        self.unmount_with(UnmountFlags::empty())
+    }
+
+    /// Unmount the device with the given flags, if it is currently mounted.
+    fn unmount_with(&mut self, flags: UnmountFlags) -> Result<(), crate::Error> {
+        if let Some(path) = self.mount_path.take() {
+            mount::unmount(path, flags)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AttachedDevice {
+    fn drop(&mut self) {
+        // Force the unmount here: if the caller already unmounted gracefully
+        // via `unmount`, `mount_path` is already `None` and this is a no-op.
+        if let Err(err) = self.unmount_with(UnmountFlags::FORCE | UnmountFlags::DETACH) {
+            eprintln!("[ERROR] Unmount failed: {err}");
+        }
+
+        let result =
+            unsafe { libcryptsetup::crypt_deactivate(self.crypt_device, self.mapped_name.1.as_ptr()) };
+        if result < 0 {
+            eprintln!("[ERROR] Crypt device deactivation failed");
+        }
+
+        unsafe { libcryptsetup::crypt_free(self.crypt_device) };
+    }
+}
+
+/// Block device attachment error.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("device does not hold a LUKS container")]
+    NotLuks,
+}
@@ -0,0 +1,146 @@
+//! `gocryptfs.conf` parsing and master-key unwrapping.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use hkdf::Hkdf;
+use rand::RngCore;
+use scrypt::Params as ScryptParams;
+use serde::Deserialize;
+use sha2::Sha256;
+
+/// Feature flags the native backend knows how to decrypt.
+///
+/// Anything outside this set falls back to the external `gocryptfs` binary.
+const SUPPORTED_FLAGS: &[&str] = &["GCMIV128", "HKDF", "DirIV", "EMENames", "LongNames", "Raw64"];
+
+/// Size of the random IV prepended to the encrypted master key.
+const KEY_IV_SIZE: usize = 16;
+
+#[derive(Deserialize)]
+struct ScryptObject {
+    #[serde(rename = "Salt")]
+    salt: String,
+    #[serde(rename = "N")]
+    n: u64,
+    #[serde(rename = "R")]
+    r: u32,
+    #[serde(rename = "P")]
+    p: u32,
+    #[serde(rename = "KeyLen")]
+    key_len: usize,
+}
+
+/// Parsed `gocryptfs.conf`.
+#[derive(Deserialize)]
+pub struct Config {
+    #[serde(rename = "EncryptedKey")]
+    encrypted_key: String,
+    #[serde(rename = "ScryptObject")]
+    scrypt: ScryptObject,
+    #[serde(rename = "FeatureFlags")]
+    feature_flags: Vec<String>,
+}
+
+impl Config {
+    /// Parse `gocryptfs.conf` at `path`.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let raw = fs::read_to_string(path)?;
+        serde_json::from_str(&raw)
+            .map_err(|err| io::Error::other(format!("invalid gocryptfs.conf: {err}")))
+    }
+
+    /// Whether every feature flag in this config is supported by the
+    /// in-process backend.
+    pub fn is_supported(&self) -> bool {
+        self.feature_flags.iter().all(|flag| SUPPORTED_FLAGS.contains(&flag.as_str()))
+    }
+
+    /// Derive the KEK from `password` and unwrap the stored master key.
+    pub fn unwrap_master_key(&self, password: &[u8]) -> io::Result<[u8; 32]> {
+        let salt = BASE64.decode(&self.scrypt.salt).map_err(|_| io::Error::other("invalid scrypt salt"))?;
+        let encrypted =
+            BASE64.decode(&self.encrypted_key).map_err(|_| io::Error::other("invalid encrypted key"))?;
+        if encrypted.len() < KEY_IV_SIZE {
+            return Err(io::Error::other("truncated encrypted master key"));
+        }
+        let (iv, ciphertext) = encrypted.split_at(KEY_IV_SIZE);
+
+        let kek = self.derive_kek(password, &salt)?;
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&kek));
+        let master_key = cipher
+            .decrypt(Nonce::from_slice(&iv[..12]), Payload { msg: ciphertext, aad: &[] })
+            .map_err(|_| io::Error::other("incorrect password"))?;
+
+        master_key.try_into().map_err(|_| io::Error::other("unexpected master key length"))
+    }
+
+    /// Derive the scrypt KEK used to wrap/unwrap the master key.
+    fn derive_kek(&self, password: &[u8], salt: &[u8]) -> io::Result<Vec<u8>> {
+        let log_n = (63 - self.scrypt.n.max(1).leading_zeros()) as u8;
+        let params = ScryptParams::new(log_n, self.scrypt.r, self.scrypt.p, self.scrypt.key_len)
+            .map_err(|_| io::Error::other("invalid scrypt parameters"))?;
+
+        let mut kek = vec![0u8; self.scrypt.key_len];
+        scrypt::scrypt(password, salt, &params, &mut kek)
+            .map_err(|_| io::Error::other("scrypt key derivation failed"))?;
+        Ok(kek)
+    }
+
+    /// Create a new `gocryptfs.conf`, wrapping a freshly generated
+    /// `master_key` with a KEK derived from `password`.
+    pub fn create(path: &Path, password: &[u8], master_key: &[u8; 32]) -> io::Result<()> {
+        const LOG_N: u8 = 16; // N = 65536, matches gocryptfs' interactive default.
+
+        let mut salt = [0u8; 32];
+        rand::rng().fill_bytes(&mut salt);
+
+        let params = ScryptParams::new(LOG_N, 8, 1, 32)
+            .map_err(|_| io::Error::other("invalid scrypt parameters"))?;
+        let mut kek = [0u8; 32];
+        scrypt::scrypt(password, &salt, &params, &mut kek)
+            .map_err(|_| io::Error::other("scrypt key derivation failed"))?;
+
+        let mut iv = [0u8; KEY_IV_SIZE];
+        rand::rng().fill_bytes(&mut iv);
+
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&kek));
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&iv[..12]), Payload { msg: master_key.as_slice(), aad: &[] })
+            .map_err(|_| io::Error::other("master key encryption failed"))?;
+
+        let mut encrypted_key = Vec::with_capacity(iv.len() + ciphertext.len());
+        encrypted_key.extend_from_slice(&iv);
+        encrypted_key.extend_from_slice(&ciphertext);
+
+        let conf = serde_json::json!({
+            "Creator": concat!("homesec v", env!("CARGO_PKG_VERSION")),
+            "EncryptedKey": BASE64.encode(encrypted_key),
+            "ScryptObject": {
+                "Salt": BASE64.encode(salt),
+                "N": 1u64 << LOG_N,
+                "R": 8,
+                "P": 1,
+                "KeyLen": 32,
+            },
+            "Version": 2,
+            "FeatureFlags": SUPPORTED_FLAGS,
+        });
+
+        fs::write(path, serde_json::to_vec_pretty(&conf)?)
+    }
+}
+
+/// Derive a per-purpose subkey from the master key via HKDF-SHA256,
+/// mirroring gocryptfs' `HKDF` feature flag.
+pub fn derive_subkey(master_key: &[u8; 32], info: &[u8]) -> [u8; 32] {
+    let hkdf = Hkdf::<Sha256>::new(None, master_key);
+    let mut out = [0u8; 32];
+    hkdf.expand(info, &mut out).expect("32 bytes is a valid HKDF-SHA256 output length");
+    out
+}
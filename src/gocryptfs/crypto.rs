@@ -0,0 +1,122 @@
+//! Per-file content encryption.
+//!
+//! Each file starts with a random 16-byte file ID, followed by 4096-byte
+//! plaintext blocks. Every block is stored on disk as `IV (16 bytes) ||
+//! ciphertext || tag (16 bytes)`, encrypted with AES-256-GCM. The file ID and
+//! block number are mixed in as additional authenticated data so blocks
+//! cannot be reordered or spliced between files undetected.
+
+use std::io;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use rand::RngCore;
+
+/// Plaintext size of a single content block.
+pub const BLOCK_SIZE: usize = 4096;
+/// Size of the random IV prepended to every block.
+const IV_SIZE: usize = 16;
+/// Size of the GCM authentication tag appended to every block.
+const TAG_SIZE: usize = 16;
+/// Size of the per-file random ID stored at the start of the file.
+const FILE_ID_SIZE: usize = 16;
+/// On-disk size of a full content block.
+const CIPHER_BLOCK_SIZE: usize = IV_SIZE + BLOCK_SIZE + TAG_SIZE;
+
+/// Decrypt a whole file, including its file ID header.
+pub fn decrypt_file(key: &[u8; 32], ciphertext: &[u8]) -> io::Result<Vec<u8>> {
+    if ciphertext.is_empty() {
+        return Ok(Vec::new());
+    }
+    if ciphertext.len() < FILE_ID_SIZE {
+        return Err(io::Error::other("truncated file header"));
+    }
+    let (file_id, mut blocks) = ciphertext.split_at(FILE_ID_SIZE);
+    let file_id: [u8; FILE_ID_SIZE] = file_id.try_into().unwrap();
+
+    let mut plaintext = Vec::with_capacity(ciphertext.len());
+    let mut block_no = 0u64;
+    while !blocks.is_empty() {
+        let take = CIPHER_BLOCK_SIZE.min(blocks.len());
+        let (block, rest) = blocks.split_at(take);
+        plaintext.extend_from_slice(&decrypt_block(key, &file_id, block_no, block)?);
+        blocks = rest;
+        block_no += 1;
+    }
+
+    Ok(plaintext)
+}
+
+/// Encrypt a whole file, generating a fresh file ID header.
+pub fn encrypt_file(key: &[u8; 32], plaintext: &[u8]) -> Vec<u8> {
+    let mut file_id = [0u8; FILE_ID_SIZE];
+    rand::rng().fill_bytes(&mut file_id);
+
+    let mut ciphertext = Vec::with_capacity(FILE_ID_SIZE + plaintext.len());
+    ciphertext.extend_from_slice(&file_id);
+
+    for (block_no, chunk) in plaintext.chunks(BLOCK_SIZE).enumerate() {
+        ciphertext.extend_from_slice(&encrypt_block(key, &file_id, block_no as u64, chunk));
+    }
+
+    ciphertext
+}
+
+/// Decrypted size of a file whose on-disk (encrypted) size is `ciphertext_len`.
+pub fn plaintext_len(ciphertext_len: u64) -> u64 {
+    if ciphertext_len == 0 {
+        return 0;
+    }
+
+    let body = ciphertext_len.saturating_sub(FILE_ID_SIZE as u64);
+    let full_blocks = body / CIPHER_BLOCK_SIZE as u64;
+    let remainder = body % CIPHER_BLOCK_SIZE as u64;
+    let last = remainder.saturating_sub((IV_SIZE + TAG_SIZE) as u64);
+
+    full_blocks * BLOCK_SIZE as u64 + last
+}
+
+/// Encrypt a single content block.
+fn encrypt_block(key: &[u8; 32], file_id: &[u8; FILE_ID_SIZE], block_no: u64, plaintext: &[u8]) -> Vec<u8> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+
+    let mut iv = [0u8; IV_SIZE];
+    rand::rng().fill_bytes(&mut iv);
+
+    let aad = block_aad(file_id, block_no);
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&iv[..12]), Payload { msg: plaintext, aad: &aad })
+        .expect("AES-256-GCM encryption of a bounded plaintext does not fail");
+
+    let mut block = Vec::with_capacity(IV_SIZE + ciphertext.len());
+    block.extend_from_slice(&iv);
+    block.extend_from_slice(&ciphertext);
+    block
+}
+
+/// Decrypt a single content block.
+fn decrypt_block(
+    key: &[u8; 32],
+    file_id: &[u8; FILE_ID_SIZE],
+    block_no: u64,
+    block: &[u8],
+) -> io::Result<Vec<u8>> {
+    if block.len() < IV_SIZE + TAG_SIZE {
+        return Err(io::Error::other("truncated content block"));
+    }
+    let (iv, ciphertext) = block.split_at(IV_SIZE);
+
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let aad = block_aad(file_id, block_no);
+    cipher
+        .decrypt(Nonce::from_slice(&iv[..12]), Payload { msg: ciphertext, aad: &aad })
+        .map_err(|_| io::Error::other("content block authentication failed"))
+}
+
+/// Additional authenticated data binding a block to its file and position.
+fn block_aad(file_id: &[u8; FILE_ID_SIZE], block_no: u64) -> [u8; FILE_ID_SIZE + 8] {
+    let mut aad = [0u8; FILE_ID_SIZE + 8];
+    aad[..FILE_ID_SIZE].copy_from_slice(file_id);
+    aad[FILE_ID_SIZE..].copy_from_slice(&block_no.to_be_bytes());
+    aad
+}
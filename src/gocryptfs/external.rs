@@ -1,18 +1,22 @@
-//! Encrypted FUSE filesystem.
+//! Fallback backend shelling out to the `gocryptfs` binary.
+//!
+//! Used when the on-disk config uses feature flags the native backend does
+//! not implement.
 
 use std::fs;
 use std::io::{self, Write};
 use std::path::PathBuf;
 use std::process::{Command, Stdio};
 
-/// Gocryptfs encrypted filesystem.
-pub struct Crypt {
+use crate::secrets::Secret;
+
+pub struct ExternalCrypt {
     storage_directory: PathBuf,
     mount_path: Option<PathBuf>,
 }
 
-impl Crypt {
-    pub fn new(storage_directory: impl Into<PathBuf>, password: &str) -> io::Result<Self> {
+impl ExternalCrypt {
+    pub fn new(storage_directory: impl Into<PathBuf>, password: &Secret) -> io::Result<Self> {
         // Ensure target directory exists.
         let storage_directory = storage_directory.into();
         fs::create_dir_all(&storage_directory)?;
@@ -37,7 +41,7 @@ impl Crypt {
     }
 
     /// Mount filesystem at the specified location.
-    pub fn mount(&mut self, path: impl Into<PathBuf>, password: &str) -> io::Result<()> {
+    pub fn mount(&mut self, path: impl Into<PathBuf>, password: &Secret) -> io::Result<()> {
         let path = path.into();
 
         let mut gocryptfs = Command::new("gocryptfs")
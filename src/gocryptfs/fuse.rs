@@ -0,0 +1,389 @@
+//! FUSE frontend exposing the decrypted gocryptfs view.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::{self, Metadata};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use fuser::{
+    FileAttr, FileType, Filesystem, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyEntry,
+    ReplyWrite, Request, TimeOrNow,
+};
+use libc::ENOENT;
+
+use super::config::derive_subkey;
+use super::{crypto, names};
+
+/// How long the kernel is allowed to cache attributes and entries for.
+const TTL: Duration = Duration::from_secs(1);
+/// Per-directory IV sidecar, see gocryptfs' `DirIV` feature flag.
+const DIRIV_FILE: &str = "gocryptfs.diriv";
+/// Names that are part of the gocryptfs format, not decryptable entries.
+const RESERVED_NAMES: &[&str] = &["gocryptfs.conf", DIRIV_FILE];
+
+/// In-process gocryptfs FUSE filesystem, backed by the plaintext view of
+/// [`super::NativeCrypt`]'s storage directory.
+pub struct GocryptFs {
+    storage_directory: PathBuf,
+    content_key: [u8; 32],
+    name_key: [u8; 32],
+    /// Inode -> decrypted path, relative to the storage directory.
+    inodes: HashMap<u64, PathBuf>,
+    next_inode: u64,
+}
+
+impl GocryptFs {
+    pub fn new(storage_directory: PathBuf, master_key: [u8; 32]) -> Self {
+        let content_key = derive_subkey(&master_key, b"AES-GCM file content encryption");
+        let name_key = derive_subkey(&master_key, b"EME filename encryption");
+
+        let mut inodes = HashMap::new();
+        inodes.insert(fuser::FUSE_ROOT_ID, PathBuf::new());
+
+        Self {
+            storage_directory,
+            content_key,
+            name_key,
+            inodes,
+            next_inode: fuser::FUSE_ROOT_ID + 1,
+        }
+    }
+
+    /// Encrypted on-disk path for a decrypted path.
+    fn encrypted_path(&self, plain_path: &Path) -> PathBuf {
+        self.storage_directory.join(plain_path)
+    }
+
+    /// Read and cache the directory IV for a decrypted directory path.
+    fn dir_iv(&self, plain_dir: &Path) -> io::Result<[u8; 16]> {
+        let raw = fs::read(self.encrypted_path(plain_dir).join(DIRIV_FILE))?;
+        raw.try_into().map_err(|_| io::Error::other("malformed gocryptfs.diriv"))
+    }
+
+    /// Look up or allocate the inode number for a decrypted path.
+    fn inode_for(&mut self, plain_path: PathBuf) -> u64 {
+        if let Some((&ino, _)) = self.inodes.iter().find(|(_, path)| **path == plain_path) {
+            return ino;
+        }
+
+        let ino = self.next_inode;
+        self.next_inode += 1;
+        self.inodes.insert(ino, plain_path);
+        ino
+    }
+
+    fn attr(&self, ino: u64, metadata: &Metadata) -> FileAttr {
+        let kind = if metadata.is_dir() { FileType::Directory } else { FileType::RegularFile };
+        let size =
+            if metadata.is_dir() { metadata.len() } else { crypto::plaintext_len(metadata.len()) };
+        let mtime = metadata.modified().unwrap_or(UNIX_EPOCH);
+
+        FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: mtime,
+            mtime,
+            ctime: mtime,
+            crtime: mtime,
+            kind,
+            perm: 0o600 | if metadata.is_dir() { 0o100 } else { 0 },
+            nlink: 1,
+            uid: rustix::process::getuid().as_raw(),
+            gid: rustix::process::getgid().as_raw(),
+            rdev: 0,
+            blksize: crypto::BLOCK_SIZE as u32,
+            flags: 0,
+        }
+    }
+
+    /// Decrypt the full contents of a file.
+    fn read_plaintext(&self, plain_path: &Path) -> io::Result<Vec<u8>> {
+        let ciphertext = fs::read(self.encrypted_path(plain_path))?;
+        crypto::decrypt_file(&self.content_key, &ciphertext)
+    }
+
+    /// Encrypt `plaintext` and overwrite a file's contents with it.
+    fn write_plaintext(&self, plain_path: &Path, plaintext: &[u8]) -> io::Result<()> {
+        let ciphertext = crypto::encrypt_file(&self.content_key, plaintext);
+        fs::write(self.encrypted_path(plain_path), ciphertext)
+    }
+
+    /// Encrypt `name` for storage inside `plain_dir`, failing entries whose
+    /// name isn't valid UTF-8.
+    fn encrypt_entry_name(&self, plain_dir: &Path, name: &OsStr) -> io::Result<String> {
+        let name = name.to_str().ok_or_else(|| io::Error::other("filename is not valid UTF-8"))?;
+        let encrypted_dir = self.encrypted_path(plain_dir);
+        let dir_iv = self.dir_iv(plain_dir)?;
+        names::encrypt_name(&self.name_key, &dir_iv, &encrypted_dir, name)
+    }
+
+    /// Decrypt the name of every entry in a directory.
+    fn read_dir_entries(&self, plain_dir: &Path) -> io::Result<Vec<(String, PathBuf, Metadata)>> {
+        let encrypted_dir = self.encrypted_path(plain_dir);
+        let dir_iv = self.dir_iv(plain_dir)?;
+
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&encrypted_dir)? {
+            let entry = entry?;
+            let encrypted_name = entry.file_name();
+            let encrypted_name = encrypted_name.to_string_lossy();
+
+            if RESERVED_NAMES.contains(&encrypted_name.as_ref())
+                || encrypted_name.ends_with(".name")
+            {
+                continue;
+            }
+
+            let name = names::decrypt_name(&self.name_key, &dir_iv, &encrypted_dir, &encrypted_name)?;
+            let metadata = entry.metadata()?;
+            entries.push((name, plain_dir.join(&encrypted_name), metadata));
+        }
+
+        Ok(entries)
+    }
+}
+
+impl Filesystem for GocryptFs {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let Some(plain_dir) = self.inodes.get(&parent).cloned() else {
+            reply.error(ENOENT);
+            return;
+        };
+
+        let entries = match self.read_dir_entries(&plain_dir) {
+            Ok(entries) => entries,
+            Err(_) => return reply.error(ENOENT),
+        };
+
+        let wanted = name.as_bytes();
+        let Some((_, encrypted_path, metadata)) =
+            entries.into_iter().find(|(decrypted, ..)| decrypted.as_bytes() == wanted)
+        else {
+            return reply.error(ENOENT);
+        };
+
+        let ino = self.inode_for(encrypted_path);
+        reply.entry(&TTL, &self.attr(ino, &metadata), 0);
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        let Some(plain_path) = self.inodes.get(&ino).cloned() else {
+            return reply.error(ENOENT);
+        };
+
+        match fs::metadata(self.encrypted_path(&plain_path)) {
+            Ok(metadata) => reply.attr(&TTL, &self.attr(ino, &metadata)),
+            Err(_) => reply.error(ENOENT),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let Some(plain_dir) = self.inodes.get(&ino).cloned() else {
+            return reply.error(ENOENT);
+        };
+
+        let entries = match self.read_dir_entries(&plain_dir) {
+            Ok(entries) => entries,
+            Err(_) => return reply.error(ENOENT),
+        };
+
+        let mut dir_entries = vec![(ino, FileType::Directory, ".".to_owned())];
+        for (name, path, metadata) in entries {
+            let kind = if metadata.is_dir() { FileType::Directory } else { FileType::RegularFile };
+            dir_entries.push((self.inode_for(path), kind, name));
+        }
+
+        for (i, (ino, kind, name)) in dir_entries.into_iter().enumerate().skip(offset as usize) {
+            if reply.add(ino, (i + 1) as i64, kind, name) {
+                break;
+            }
+        }
+
+        reply.ok();
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let Some(plain_path) = self.inodes.get(&ino).cloned() else {
+            return reply.error(ENOENT);
+        };
+
+        let ciphertext = match fs::read(self.encrypted_path(&plain_path)) {
+            Ok(data) => data,
+            Err(_) => return reply.error(ENOENT),
+        };
+
+        let plaintext = match crypto::decrypt_file(&self.content_key, &ciphertext) {
+            Ok(data) => data,
+            Err(_) => return reply.error(libc::EIO),
+        };
+
+        let start = (offset as usize).min(plaintext.len());
+        let end = (start + size as usize).min(plaintext.len());
+        reply.data(&plaintext[start..end]);
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        let Some(plain_path) = self.inodes.get(&ino).cloned() else {
+            return reply.error(ENOENT);
+        };
+
+        let mut plaintext = match self.read_plaintext(&plain_path) {
+            Ok(plaintext) => plaintext,
+            Err(_) => return reply.error(libc::EIO),
+        };
+
+        let offset = offset as usize;
+        let end = offset + data.len();
+        if plaintext.len() < end {
+            plaintext.resize(end, 0);
+        }
+        plaintext[offset..end].copy_from_slice(data);
+
+        if self.write_plaintext(&plain_path, &plaintext).is_err() {
+            return reply.error(libc::EIO);
+        }
+
+        reply.written(data.len() as u32);
+    }
+
+    fn create(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        _flags: i32,
+        reply: ReplyCreate,
+    ) {
+        let Some(plain_dir) = self.inodes.get(&parent).cloned() else {
+            return reply.error(ENOENT);
+        };
+
+        let encrypted_name = match self.encrypt_entry_name(&plain_dir, name) {
+            Ok(encrypted_name) => encrypted_name,
+            Err(_) => return reply.error(libc::EIO),
+        };
+
+        let plain_path = plain_dir.join(&encrypted_name);
+        if self.write_plaintext(&plain_path, &[]).is_err() {
+            return reply.error(libc::EIO);
+        }
+
+        let metadata = match fs::metadata(self.encrypted_path(&plain_path)) {
+            Ok(metadata) => metadata,
+            Err(_) => return reply.error(libc::EIO),
+        };
+
+        let ino = self.inode_for(plain_path);
+        reply.created(&TTL, &self.attr(ino, &metadata), 0, 0, 0);
+    }
+
+    fn mkdir(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        reply: ReplyEntry,
+    ) {
+        let Some(plain_dir) = self.inodes.get(&parent).cloned() else {
+            return reply.error(ENOENT);
+        };
+
+        let encrypted_name = match self.encrypt_entry_name(&plain_dir, name) {
+            Ok(encrypted_name) => encrypted_name,
+            Err(_) => return reply.error(libc::EIO),
+        };
+
+        let plain_path = plain_dir.join(&encrypted_name);
+        let encrypted_path = self.encrypted_path(&plain_path);
+        if fs::create_dir(&encrypted_path).is_err() {
+            return reply.error(libc::EIO);
+        }
+        if fs::write(encrypted_path.join(DIRIV_FILE), super::random_dir_iv()).is_err() {
+            return reply.error(libc::EIO);
+        }
+
+        let metadata = match fs::metadata(&encrypted_path) {
+            Ok(metadata) => metadata,
+            Err(_) => return reply.error(libc::EIO),
+        };
+
+        let ino = self.inode_for(plain_path);
+        reply.entry(&TTL, &self.attr(ino, &metadata), 0);
+    }
+
+    fn setattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        let Some(plain_path) = self.inodes.get(&ino).cloned() else {
+            return reply.error(ENOENT);
+        };
+
+        if let Some(size) = size {
+            let mut plaintext = match self.read_plaintext(&plain_path) {
+                Ok(plaintext) => plaintext,
+                Err(_) => return reply.error(libc::EIO),
+            };
+            plaintext.resize(size as usize, 0);
+
+            if self.write_plaintext(&plain_path, &plaintext).is_err() {
+                return reply.error(libc::EIO);
+            }
+        }
+
+        match fs::metadata(self.encrypted_path(&plain_path)) {
+            Ok(metadata) => reply.attr(&TTL, &self.attr(ino, &metadata)),
+            Err(_) => reply.error(ENOENT),
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Encrypted FUSE filesystem.
+//!
+//! Content and filenames are decrypted in-process; see [`config`], [`crypto`]
+//! and [`names`] for the on-disk format and [`fuse`] for the FUSE frontend.
+//! Configurations using feature flags we do not implement fall back to
+//! [`external`], which shells out to the `gocryptfs` binary like before.
+
+mod config;
+mod crypto;
+mod eme;
+mod external;
+mod fuse;
+mod names;
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use rand::RngCore;
+
+use self::config::Config;
+use self::external::ExternalCrypt;
+use self::fuse::GocryptFs;
+use crate::secrets::Secret;
+
+/// Name of the gocryptfs master config file.
+const CONF_FILE: &str = "gocryptfs.conf";
+
+/// Gocryptfs encrypted filesystem.
+pub struct Crypt {
+    backend: Backend,
+}
+
+/// Filesystem backend, picked based on what the on-disk config supports.
+enum Backend {
+    /// Pure-Rust in-process implementation.
+    Native(NativeCrypt),
+    /// Fallback shelling out to the `gocryptfs` binary.
+    External(ExternalCrypt),
+}
+
+impl Crypt {
+    pub fn new(storage_directory: impl Into<PathBuf>, password: &Secret) -> io::Result<Self> {
+        let storage_directory = storage_directory.into();
+        fs::create_dir_all(&storage_directory)?;
+
+        let conf_path = storage_directory.join(CONF_FILE);
+        let backend = if !conf_path.exists() {
+            Backend::Native(NativeCrypt::init(storage_directory, password.as_bytes())?)
+        } else {
+            let config = Config::load(&conf_path)?;
+            if config.is_supported() {
+                Backend::Native(NativeCrypt::open(storage_directory, &config, password.as_bytes())?)
+            } else {
+                Backend::External(ExternalCrypt::new(storage_directory, password)?)
+            }
+        };
+
+        Ok(Self { backend })
+    }
+
+    /// Mount filesystem at the specified location.
+    pub fn mount(&mut self, path: impl Into<PathBuf>, password: &Secret) -> io::Result<()> {
+        match &mut self.backend {
+            Backend::Native(native) => native.mount(path.into()),
+            Backend::External(external) => external.mount(path, password),
+        }
+    }
+
+    /// Unmount the filesystem.
+    pub fn _unmount(&mut self) -> io::Result<()> {
+        match &mut self.backend {
+            Backend::Native(native) => native.unmount(),
+            Backend::External(external) => external._unmount(),
+        }
+    }
+}
+
+/// In-process gocryptfs backend.
+struct NativeCrypt {
+    storage_directory: PathBuf,
+    master_key: [u8; 32],
+    mount_path: Option<PathBuf>,
+    session: Option<fuser::BackgroundSession>,
+}
+
+impl NativeCrypt {
+    /// Initialize a brand new gocryptfs directory.
+    fn init(storage_directory: PathBuf, password: &[u8]) -> io::Result<Self> {
+        let mut master_key = [0u8; 32];
+        rand::rng().fill_bytes(&mut master_key);
+
+        Config::create(&storage_directory.join(CONF_FILE), password, &master_key)?;
+        fs::write(storage_directory.join("gocryptfs.diriv"), random_dir_iv())?;
+
+        Ok(Self { storage_directory, master_key, mount_path: None, session: None })
+    }
+
+    /// Open an existing gocryptfs directory.
+    fn open(storage_directory: PathBuf, config: &Config, password: &[u8]) -> io::Result<Self> {
+        let master_key = config.unwrap_master_key(password)?;
+        Ok(Self { storage_directory, master_key, mount_path: None, session: None })
+    }
+
+    fn mount(&mut self, path: PathBuf) -> io::Result<()> {
+        let fs = GocryptFs::new(self.storage_directory.clone(), self.master_key);
+        let options = [fuser::MountOption::FSName("gocryptfs".into())];
+        self.session = Some(fuser::spawn_mount2(fs, &path, &options)?);
+        self.mount_path = Some(path);
+        Ok(())
+    }
+
+    fn unmount(&mut self) -> io::Result<()> {
+        self.mount_path.take();
+        self.session.take();
+        Ok(())
+    }
+}
+
+/// Generate a random directory IV for a freshly created directory.
+pub(crate) fn random_dir_iv() -> [u8; 16] {
+    let mut iv = [0u8; 16];
+    rand::rng().fill_bytes(&mut iv);
+    iv
+}
@@ -0,0 +1,110 @@
+//! Filename encryption.
+//!
+//! Names are EME-encrypted with the dedicated name key, tweaked by the
+//! directory's IV, and base64url-encoded for storage. Names whose encoded
+//! form would not fit in a single directory entry are instead stored as a
+//! `gocryptfs.name` sidecar next to a `gocryptfs.longname.<hash>` stand-in.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64;
+use sha2::{Digest, Sha256};
+
+use super::eme::Eme;
+
+/// Prefix used for names that overflow into a `gocryptfs.name` sidecar.
+const LONGNAME_PREFIX: &str = "gocryptfs.longname.";
+/// Maximum length of an encoded name before it spills into a sidecar.
+const MAX_ENCODED_NAME_LEN: usize = 255;
+/// EME operates on 16-byte blocks; names are padded to a multiple of this
+/// before encryption.
+const PAD_BLOCK_SIZE: usize = 16;
+
+/// Decrypt a single directory entry name.
+///
+/// `dir_iv` is the per-directory tweak read from that directory's
+/// `gocryptfs.diriv` file, and `dir` is the corresponding encrypted
+/// directory, used to resolve long-name sidecars.
+pub fn decrypt_name(
+    name_key: &[u8; 32],
+    dir_iv: &[u8; 16],
+    dir: &Path,
+    encrypted_name: &str,
+) -> io::Result<String> {
+    let encoded = match encrypted_name.strip_prefix(LONGNAME_PREFIX) {
+        Some(hash) => fs::read_to_string(dir.join(format!("{LONGNAME_PREFIX}{hash}.name")))?,
+        None => encrypted_name.to_owned(),
+    };
+
+    let ciphertext =
+        BASE64.decode(encoded.trim_end()).map_err(|_| io::Error::other("invalid base64 filename"))?;
+    let padded = Eme::new(name_key)
+        .decrypt(dir_iv, &ciphertext)
+        .map_err(|_| io::Error::other("filename decryption failed"))?;
+    let plaintext = unpad16(&padded)?;
+
+    String::from_utf8(plaintext).map_err(|_| io::Error::other("decrypted filename is not valid UTF-8"))
+}
+
+/// Encrypt a single directory entry name, spilling to a `gocryptfs.name`
+/// sidecar when the encoded result would be too long for a directory entry.
+pub fn encrypt_name(name_key: &[u8; 32], dir_iv: &[u8; 16], dir: &Path, name: &str) -> io::Result<String> {
+    let padded = pad16(name.as_bytes());
+    let ciphertext = Eme::new(name_key)
+        .encrypt(dir_iv, &padded)
+        .map_err(|_| io::Error::other("filename encryption failed"))?;
+    let encoded = BASE64.encode(ciphertext);
+
+    if encoded.len() <= MAX_ENCODED_NAME_LEN {
+        return Ok(encoded);
+    }
+
+    let hash = BASE64.encode(Sha256::digest(encoded.as_bytes()));
+    let long_name = format!("{LONGNAME_PREFIX}{hash}");
+    fs::write(dir.join(format!("{long_name}.name")), &encoded)?;
+
+    Ok(long_name)
+}
+
+/// Pad `data` to a non-zero multiple of 16 bytes, gocryptfs-style: every
+/// padding byte holds the pad length (PKCS#7), and already-aligned input
+/// still gets a full block of padding so it's never ambiguous to strip.
+fn pad16(data: &[u8]) -> Vec<u8> {
+    let pad_len = PAD_BLOCK_SIZE - (data.len() % PAD_BLOCK_SIZE);
+    let mut padded = Vec::with_capacity(data.len() + pad_len);
+    padded.extend_from_slice(data);
+    padded.extend(std::iter::repeat(pad_len as u8).take(pad_len));
+    padded
+}
+
+/// Reverse [`pad16`], rejecting malformed padding.
+fn unpad16(data: &[u8]) -> io::Result<Vec<u8>> {
+    let pad_len = *data.last().ok_or_else(|| io::Error::other("empty padded filename"))? as usize;
+    let valid = pad_len > 0
+        && pad_len <= PAD_BLOCK_SIZE
+        && pad_len <= data.len()
+        && data[data.len() - pad_len..].iter().all(|&b| b as usize == pad_len);
+    if !valid {
+        return Err(io::Error::other("invalid filename padding"));
+    }
+
+    Ok(data[..data.len() - pad_len].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pad16_round_trips_short_and_aligned_names() {
+        for name in ["a", "README.md", "0123456789abcdef"] {
+            let padded = pad16(name.as_bytes());
+            assert_eq!(padded.len() % PAD_BLOCK_SIZE, 0);
+            assert!(!padded.is_empty());
+            assert_eq!(unpad16(&padded).unwrap(), name.as_bytes());
+        }
+    }
+}
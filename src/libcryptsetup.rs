@@ -0,0 +1,73 @@
+//! Minimal FFI bindings for `libcryptsetup`.
+//!
+//! See <https://mbroz.fedorapeople.org/libcryptsetup_API/> for the upstream
+//! API documentation. Only the subset of calls `luks` currently needs is
+//! bound here.
+
+use std::ffi::{c_char, c_int, c_void};
+
+/// Opaque `libcryptsetup` device handle.
+#[repr(C)]
+pub struct crypt_device {
+    _private: [u8; 0],
+}
+
+/// Let libcryptsetup pick any free keyslot.
+pub const CRYPT_ANY_SLOT: c_int = -1;
+
+/// `crypt_keyslot_info` values indicating an in-use keyslot.
+pub const CRYPT_SLOT_ACTIVE: c_int = 1;
+pub const CRYPT_SLOT_ACTIVE_LAST: c_int = 2;
+
+#[link(name = "cryptsetup")]
+extern "C" {
+    pub fn crypt_init(cd: *mut *mut crypt_device, device: *const c_char) -> c_int;
+
+    pub fn crypt_load(cd: *mut crypt_device, requested_type: *const c_char, params: *mut c_void) -> c_int;
+
+    pub fn crypt_format(
+        cd: *mut crypt_device,
+        requested_type: *const c_char,
+        cipher: *const c_char,
+        cipher_mode: *const c_char,
+        uuid: *const c_char,
+        volume_key: *const c_char,
+        volume_key_size: usize,
+        params: *mut c_void,
+    ) -> c_int;
+
+    pub fn crypt_keyslot_add_by_volume_key(
+        cd: *mut crypt_device,
+        keyslot: c_int,
+        volume_key: *const c_char,
+        volume_key_size: usize,
+        passphrase: *const c_char,
+        passphrase_size: usize,
+    ) -> c_int;
+
+    pub fn crypt_keyslot_add_by_passphrase(
+        cd: *mut crypt_device,
+        keyslot: c_int,
+        passphrase: *const c_char,
+        passphrase_size: usize,
+        new_passphrase: *const c_char,
+        new_passphrase_size: usize,
+    ) -> c_int;
+
+    pub fn crypt_keyslot_destroy(cd: *mut crypt_device, keyslot: c_int) -> c_int;
+
+    pub fn crypt_keyslot_status(cd: *mut crypt_device, keyslot: c_int) -> c_int;
+
+    pub fn crypt_activate_by_passphrase(
+        cd: *mut crypt_device,
+        name: *const c_char,
+        keyslot: c_int,
+        passphrase: *const c_char,
+        passphrase_size: usize,
+        flags: u32,
+    ) -> c_int;
+
+    pub fn crypt_deactivate(cd: *mut crypt_device, name: *const c_char) -> c_int;
+
+    pub fn crypt_free(cd: *mut crypt_device);
+}
@@ -1,11 +1,12 @@
 //! File-backed encrypted mount.
 
 use std::ffi::{CStr, CString};
-use std::fs::File;
+use std::fs::{self as stdfs, File};
+use std::io::Write;
 use std::mem::MaybeUninit;
 use std::os::fd::AsFd;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
 use std::ptr;
 
 use rustix::fs::{self, FallocateFlags};
@@ -13,6 +14,10 @@ use rustix::mount::{self, MountFlags, UnmountFlags};
 use uuid::Uuid;
 
 use crate::libcryptsetup;
+use crate::secrets::Secret;
+
+/// Highest keyslot index LUKS2 can address.
+const MAX_KEYSLOTS: u32 = 32;
 
 pub struct Crypt {
     crypt_device: *mut libcryptsetup::crypt_device,
@@ -21,14 +26,29 @@ pub struct Crypt {
     mount_path: Option<PathBuf>,
 }
 
+/// Status of a single LUKS keyslot.
+#[derive(Debug, Clone, Copy)]
+pub struct KeyslotInfo {
+    pub slot: u32,
+    pub active: bool,
+}
+
 impl Crypt {
-    /// Create a new encrypted file.
-    pub fn new(path: impl Into<PathBuf>, size: u64) -> Result<Self, crate::Error> {
+    /// Create a new encrypted file, or open an existing one.
+    ///
+    /// `sudo` is only needed when creating a brand new file: formatting the
+    /// mapped device with `mkfs.ext4` requires privileges the sandbox's user
+    /// namespace doesn't carry.
+    pub fn new(
+        path: impl Into<PathBuf>,
+        size: u64,
+        passphrase: &Secret,
+        sudo: Option<&Secret>,
+    ) -> Result<Self, crate::Error> {
         // See https://mbroz.fedorapeople.org/libcryptsetup_API.
 
         let path = path.into();
-
-        let passphrase = c"todo:pass"; // TODO
+        let passphrase = passphrase.as_cstr();
 
         if path.exists() {
             // Initialize crypt device.
@@ -38,7 +58,7 @@ impl Crypt {
             crypt.load_luks()?;
 
             // Map the crypt device.
-            crypt.map(passphrase)?;
+            crypt.map(&path, passphrase)?;
 
             Ok(crypt)
         } else {
@@ -53,15 +73,39 @@ impl Crypt {
             crypt.setup_encryption(passphrase)?;
 
             // Map the crypt device.
-            crypt.map(passphrase)?;
+            crypt.map(&path, passphrase)?;
 
             // Create ext4 filesystem.
-            crypt.mkfs_ext4()?;
+            crypt.mkfs_ext4(sudo)?;
 
             Ok(crypt)
         }
     }
 
+    /// Open an existing encrypted file, trying `keyfile` before falling back
+    /// to `passphrase`, the same secret the caller will go on to use to
+    /// authorize any keyslot change.
+    pub fn open_with_keyfile(
+        path: impl Into<PathBuf>,
+        keyfile: Option<&Path>,
+        passphrase: &Secret,
+    ) -> Result<Self, crate::Error> {
+        let path = path.into();
+
+        if let Some(keyfile) = keyfile {
+            let key = stdfs::read(keyfile)?;
+            if let Ok(key) = CString::new(key) {
+                let mut crypt = Self::init(&path)?;
+                crypt.load_luks()?;
+                if crypt.map(&path, &key).is_ok() {
+                    return Ok(crypt);
+                }
+            }
+        }
+
+        Self::new(path, 0, passphrase, None)
+    }
+
     /// Mount filesystem at `path`.
     pub fn mount(&mut self, path: impl Into<PathBuf>) -> Result<(), crate::Error> {
         let mapped_name = match &self.mapped_name {
@@ -78,6 +122,67 @@ impl Crypt {
         Ok(())
     }
 
+    /// Add a new passphrase keyslot, unlocked by an existing passphrase.
+    pub fn add_passphrase(
+        &self,
+        unlock: &Secret,
+        new_passphrase: &Secret,
+    ) -> Result<u32, crate::Error> {
+        let unlock = unlock.as_cstr();
+        let new_passphrase = new_passphrase.as_cstr();
+        self.add_keyslot(unlock, new_passphrase)
+    }
+
+    /// Add a new keyfile-backed keyslot, unlocked by an existing passphrase.
+    pub fn add_keyfile(&self, unlock: &Secret, keyfile: &Path) -> Result<u32, crate::Error> {
+        let unlock = unlock.as_cstr();
+        let key = CString::new(stdfs::read(keyfile)?)?;
+        self.add_keyslot(unlock, &key)
+    }
+
+    /// Add a new keyslot protected by `new_key`, unlocked with `unlock`.
+    fn add_keyslot(&self, unlock: &CStr, new_key: &CStr) -> Result<u32, crate::Error> {
+        let result = unsafe {
+            libcryptsetup::crypt_keyslot_add_by_passphrase(
+                self.crypt_device,
+                libcryptsetup::CRYPT_ANY_SLOT,
+                unlock.as_ptr(),
+                unlock.count_bytes(),
+                new_key.as_ptr(),
+                new_key.count_bytes(),
+            )
+        };
+        if result < 0 {
+            return Err(Error::AddKeyslot.into());
+        }
+
+        Ok(result as u32)
+    }
+
+    /// Permanently wipe a keyslot.
+    pub fn remove_keyslot(&self, slot: u32) -> Result<(), crate::Error> {
+        let result = unsafe { libcryptsetup::crypt_keyslot_destroy(self.crypt_device, slot as i32) };
+        if result < 0 {
+            return Err(Error::RemoveKeyslot.into());
+        }
+        Ok(())
+    }
+
+    /// Enumerate the status of every keyslot this device can hold.
+    pub fn list_keyslots(&self) -> Vec<KeyslotInfo> {
+        (0..MAX_KEYSLOTS)
+            .map(|slot| {
+                let status =
+                    unsafe { libcryptsetup::crypt_keyslot_status(self.crypt_device, slot as i32) };
+                let active = matches!(
+                    status,
+                    libcryptsetup::CRYPT_SLOT_ACTIVE | libcryptsetup::CRYPT_SLOT_ACTIVE_LAST
+                );
+                KeyslotInfo { slot, active }
+            })
+            .collect()
+    }
+
     /// Initialize a crypt device for the specified path.
     fn init(path: &Path) -> Result<Self, crate::Error> {
         let c_path = CString::new(path.as_os_str().as_encoded_bytes())?;
@@ -147,9 +252,12 @@ impl Crypt {
     // TODO: Requires admin permissions, what do?
     //
     /// Map crypt device.
-    fn map(&mut self, passphrase: &CStr) -> Result<(), crate::Error> {
-        // Create mapped device name.
-        let mapped_name = format!("homesec-{}", Uuid::new_v4());
+    ///
+    /// The chosen device-mapper name is persisted next to `path`, so that
+    /// reopening the same `.homesec` file later reuses the same name instead
+    /// of mapping under a fresh random one every time.
+    fn map(&mut self, path: &Path, passphrase: &CStr) -> Result<(), crate::Error> {
+        let mapped_name = mapped_name_for(path)?;
         let c_mapped_name = CString::new(mapped_name.as_bytes())?;
 
         let result = unsafe {
@@ -174,14 +282,36 @@ impl Crypt {
     // TODO: Shelling out to mkfs.ext4 sucks, maybe a different FS is easier?
     //
     /// Create ext4 filesystem.
-    fn mkfs_ext4(&self) -> Result<(), crate::Error> {
+    ///
+    /// The mapped device lives outside the sandbox's user namespace, so
+    /// formatting it needs real privileges; `sudo` is piped the escalation
+    /// password over stdin the same way the gocryptfs external backend pipes
+    /// its passphrase.
+    fn mkfs_ext4(&self, sudo: Option<&Secret>) -> Result<(), crate::Error> {
         let mapped_name = match &self.mapped_name {
             Some((mapped_name, _)) => mapped_name,
             None => return Err(Error::Unmapped.into()),
         };
 
         let mapper_path = PathBuf::from("/dev/mapper").join(mapped_name);
-        let mut mkfs = Command::new("mkfs.ext4").arg("-q").arg(mapper_path).spawn()?;
+
+        let mut mkfs = match sudo {
+            Some(sudo) => {
+                let mut mkfs = Command::new("sudo")
+                    .arg("-S")
+                    .arg("mkfs.ext4")
+                    .arg("-q")
+                    .arg(mapper_path)
+                    .stdin(Stdio::piped())
+                    .spawn()?;
+                let mut stdin = mkfs.stdin.take().unwrap();
+                stdin.write_all(sudo.as_bytes())?;
+                stdin.write_all(b"\n")?;
+                mkfs
+            },
+            None => Command::new("mkfs.ext4").arg("-q").arg(mapper_path).spawn()?,
+        };
+
         if !mkfs.wait()?.success() {
             return Err(Error::Mkfs.into());
         }
@@ -192,7 +322,6 @@ impl Crypt {
 
 impl Drop for Crypt {
     fn drop(&mut self) {
-        println!("DROPPING"); // TODO
         if self.crypt_device.is_null() {
             return;
         }
@@ -218,6 +347,26 @@ impl Drop for Crypt {
     }
 }
 
+/// Name of the sidecar file storing the persisted device-mapper name.
+const DM_NAME_EXT: &str = "dmname";
+
+/// Get the device-mapper name to map `path` under, generating and persisting
+/// a fresh one next to it if this is the first time it's mapped.
+fn mapped_name_for(path: &Path) -> Result<String, crate::Error> {
+    let name_file = path.with_extension(DM_NAME_EXT);
+
+    if let Ok(existing) = stdfs::read_to_string(&name_file) {
+        let trimmed = existing.trim();
+        if !trimmed.is_empty() {
+            return Ok(trimmed.to_owned());
+        }
+    }
+
+    let mapped_name = format!("homesec-{}", Uuid::new_v4());
+    stdfs::write(&name_file, &mapped_name)?;
+    Ok(mapped_name)
+}
+
 /// Cryptsetup error.
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -229,6 +378,8 @@ pub enum Error {
     Format,
     #[error("keyslot addition failed")]
     AddKeyslot,
+    #[error("keyslot removal failed")]
+    RemoveKeyslot,
     #[error("crypt device mapping failed")]
     Map,
     #[error("crypt device unmapping failed")]
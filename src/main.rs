@@ -1,24 +1,52 @@
-use std::fs::File;
-use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
 use std::process::{self, Command};
+use std::str::FromStr;
 use std::{fs, io};
 
 use argh::FromArgs;
-use io::Write;
 use rustix::mount::{self, MountFlags};
-use rustix::termios::{self, LocalModes, OptionalActions};
 use rustix::thread::{Gid, Uid, UnshareFlags};
 use xdg::BaseDirectories;
 
-use crate::gocryptfs::Crypt;
+use crate::secrets::{PasswordHolder, Secret};
 
+mod blockdev;
 mod gocryptfs;
+mod libcryptsetup;
+mod luks;
 mod namespaces;
+mod secrets;
 
 /// Read-write location of the root directory inside the namespace.
 const WRITE_ROOT: &str = "/tmp/write-root";
 
+/// Directory attached block devices are mounted under.
+const ATTACH_ROOT: &str = "/mnt";
+
+/// Default size of a freshly created LUKS-backed home, in bytes.
+const DEFAULT_LUKS_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+
+/// Encrypted home filesystem backend.
+#[derive(Clone, Copy)]
+enum Backend {
+    /// In-process gocryptfs-compatible filesystem.
+    Gocryptfs,
+    /// File-backed LUKS container, mounted via `libcryptsetup`.
+    Luks,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gocryptfs" => Ok(Self::Gocryptfs),
+            "luks" => Ok(Self::Luks),
+            _ => Err(format!("unknown backend '{s}', expected 'gocryptfs' or 'luks'")),
+        }
+    }
+}
+
 /// Run applications with an isolated filesystem.
 #[derive(FromArgs)]
 struct Args {
@@ -30,6 +58,47 @@ struct Args {
     #[argh(option, short = 'i')]
     id: Option<String>,
 
+    /// encrypted home backend: "gocryptfs" (default) or "luks"
+    #[argh(option, default = "Backend::Gocryptfs")]
+    backend: Backend,
+
+    /// size of a freshly created LUKS-backed home, in bytes
+    #[argh(option, default = "DEFAULT_LUKS_SIZE")]
+    size: u64,
+
+    /// read the encryption passphrase from this file instead of prompting
+    #[argh(option)]
+    keyfile: Option<PathBuf>,
+
+    /// read the sudo password from this file instead of prompting, for
+    /// operations that need to escalate privileges
+    #[argh(option)]
+    sudo_keyfile: Option<PathBuf>,
+
+    /// add a keyfile-backed keyslot to the LUKS-backed home, then exit
+    #[argh(option)]
+    add_keyfile: Option<PathBuf>,
+
+    /// permanently remove a keyslot from the LUKS-backed home, then exit
+    #[argh(option)]
+    remove_keyslot: Option<u32>,
+
+    /// print the status of every keyslot on the LUKS-backed home, then exit
+    #[argh(switch)]
+    list_keyslots: bool,
+
+    /// attach an existing encrypted block device by UUID or path
+    #[argh(option)]
+    attach: Option<String>,
+
+    /// mount the device given to `--attach` read-only instead of read-write
+    #[argh(switch)]
+    attach_readonly: bool,
+
+    /// run the command in the host's PID namespace instead of a fresh one
+    #[argh(switch)]
+    no_pid_namespace: bool,
+
     /// command which will be executed
     #[argh(positional)]
     cmd: String,
@@ -39,6 +108,15 @@ struct Args {
     args: Vec<String>,
 }
 
+/// The encrypted home filesystem, regardless of which [`Backend`] created it.
+///
+/// Held alive for the lifetime of the sandboxed command; dropping it tears
+/// down the mount.
+enum HomeCrypt {
+    Gocryptfs(gocryptfs::Crypt),
+    Luks(luks::Crypt),
+}
+
 fn main() {
     let args: Args = argh::from_env();
 
@@ -49,7 +127,7 @@ fn main() {
         process::exit(1);
     }
 
-    // Get gocryptfs storage directory.
+    // Get encrypted home storage directory.
     let crypt_dir = if args.ephemeral {
         None
     } else {
@@ -64,25 +142,97 @@ fn main() {
         Some(dirs.get_data_file(format!("{crypt_id}.homesec")))
     };
 
-    // Create our target filesystem.
-    if let Err(err) = readonly_root(crypt_dir.as_deref()) {
-        eprintln!("[ERROR] Failed to create readonly root: {err}");
-        process::exit(255);
+    // Keyslot management operates directly on an existing LUKS-backed home
+    // and never launches the sandboxed command.
+    if args.add_keyfile.is_some() || args.remove_keyslot.is_some() || args.list_keyslots {
+        let crypt_dir = crypt_dir.as_deref().unwrap_or_else(|| {
+            eprintln!("[ERROR] Keyslot management requires a persistent (non-ephemeral) home");
+            process::exit(1);
+        });
+
+        if let Err(err) = manage_keyslots(crypt_dir, &args) {
+            eprintln!("[ERROR] Keyslot management failed: {err}");
+            process::exit(255);
+        }
+
+        process::exit(0);
     }
 
-    // Launch user executable.
-    let mut cmd = Command::new(args.cmd);
-    for arg in args.args {
-        cmd.arg(arg);
+    // Create our target filesystem. Both returned values must stay alive for
+    // the rest of `main`: they own the mounts backing the encrypted home and
+    // the attached device, and dropping either early unmounts it before the
+    // sandboxed command gets to run.
+    let attach = args.attach.as_deref();
+    let (home_crypt, attached_device) = match readonly_root(
+        crypt_dir.as_deref(),
+        args.backend,
+        args.size,
+        args.keyfile.as_deref(),
+        args.sudo_keyfile.as_deref(),
+        attach,
+        args.attach_readonly,
+    ) {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("[ERROR] Failed to create readonly root: {err}");
+            process::exit(255);
+        },
+    };
+
+    // Launch user executable, isolated in its own PID namespace by default.
+    let exit_code = if args.no_pid_namespace {
+        run_command(&args.cmd, &args.args)
+    } else {
+        match namespaces::run_in_pid_namespace(|| run_command(&args.cmd, &args.args)) {
+            Ok(exit_code) => exit_code,
+            Err(err) => {
+                eprintln!("[ERROR] Failed to create PID namespace: {err}");
+                process::exit(255);
+            },
+        }
+    };
+
+    // The command has exited; unmount the encrypted home and attached device
+    // before we do. The attached device is unmounted explicitly (rather than
+    // just relying on its `Drop` impl) so a graceful unmount is attempted
+    // before the device is deactivated.
+    drop(home_crypt);
+    if let Some(mut attached_device) = attached_device {
+        if let Err(err) = attached_device.unmount() {
+            eprintln!("[ERROR] Failed to unmount attached device: {err}");
+        }
+    }
+
+    process::exit(exit_code);
+}
+
+/// Spawn `cmd` with `args` and wait for it to exit, returning its exit code.
+fn run_command(cmd: &str, args: &[String]) -> i32 {
+    let mut command = Command::new(cmd);
+    command.args(args);
+
+    match command.spawn().and_then(|mut child| child.wait()) {
+        Ok(status) => status.code().unwrap_or(1),
+        Err(err) => {
+            eprintln!("[ERROR] Failed to launch command: {err}");
+            255
+        },
     }
-    cmd.spawn().unwrap().wait().unwrap();
 }
 
 /// Switch to a readonly version of the filesystem.
 ///
 /// The old root will be mounted in read-write mode at [`WRITE_ROOT`] inside the
 /// new root, allowing manually persisting data to the filesystem.
-fn readonly_root(crypt_dir: Option<&Path>) -> io::Result<()> {
+fn readonly_root(
+    crypt_dir: Option<&Path>,
+    backend: Backend,
+    size: u64,
+    keyfile: Option<&Path>,
+    sudo_keyfile: Option<&Path>,
+    attach: Option<&str>,
+    attach_readonly: bool,
+) -> Result<(Option<HomeCrypt>, Option<blockdev::AttachedDevice>), Error> {
     let home = home::home_dir();
     let euid = rustix::process::geteuid();
     let egid = rustix::process::getegid();
@@ -110,42 +260,137 @@ fn readonly_root(crypt_dir: Option<&Path>) -> io::Result<()> {
     namespaces::pivot_root("/tmp", &write_root)?;
 
     // Create fake home directory.
-    if let Some(home) = home {
-        create_home(&home, crypt_dir)?;
-    }
+    let home_crypt = match home {
+        Some(home) => {
+            // Only the encrypted home needs a secret. The LUKS backend may
+            // need to shell out to `mkfs.ext4` with elevated privileges when
+            // creating a brand new home; gocryptfs never does.
+            let need_sudo = matches!(backend, Backend::Luks);
+            let secrets = crypt_dir
+                .is_some()
+                .then(|| PasswordHolder::new(keyfile, need_sudo, sudo_keyfile))
+                .transpose()?;
+            create_home(&home, crypt_dir, backend, size, secrets.as_ref())?
+        },
+        None => None,
+    };
+
+    // Attach an existing encrypted block device, if one was requested.
+    let attached_device =
+        attach.map(|selector| attach_device(selector, attach_readonly)).transpose()?;
 
     // Drop user namespace permissions.
     namespaces::create_user_namespace(euid, egid, UnshareFlags::empty())?;
 
-    Ok(())
+    Ok((home_crypt, attached_device))
 }
 
 /// Create a fake home directory.
 ///
 /// This will map a temporary directory over the user's home directory and do
 /// just enough to ensure graphical applications are able to start.
-fn create_home(home: &Path, crypt_dir: Option<&Path>) -> io::Result<()> {
+fn create_home(
+    home: &Path,
+    crypt_dir: Option<&Path>,
+    backend: Backend,
+    size: u64,
+    secrets: Option<&PasswordHolder>,
+) -> Result<Option<HomeCrypt>, Error> {
     // Get home directory path inside write root.
     let write_home = join_absolute_paths(WRITE_ROOT, home);
 
-    match crypt_dir {
+    let crypt = match crypt_dir {
         // Create encrypted home directory.
         Some(crypt_dir) => {
-            let password = read_password()?;
+            let secrets = secrets.expect("encrypted home requires a passphrase");
             let write_crypt_dir = join_absolute_paths(WRITE_ROOT, crypt_dir);
-            let mut crypt = Crypt::new(write_crypt_dir, &password)?;
-            crypt.mount(home, &password)?;
+
+            let crypt = match backend {
+                Backend::Gocryptfs => {
+                    let mut crypt = gocryptfs::Crypt::new(write_crypt_dir, &secrets.encryption)?;
+                    crypt.mount(home, &secrets.encryption)?;
+                    HomeCrypt::Gocryptfs(crypt)
+                },
+                Backend::Luks => {
+                    let mut crypt = luks::Crypt::new(
+                        write_crypt_dir,
+                        size,
+                        &secrets.encryption,
+                        secrets.sudo.as_ref(),
+                    )?;
+                    crypt.mount(home)?;
+                    HomeCrypt::Luks(crypt)
+                },
+            };
+
+            Some(crypt)
         },
         // Create ephemeral home directory.
-        None => mount::mount2(None::<&str>, home, Some("tmpfs"), MountFlags::empty(), None)?,
-    }
+        None => {
+            mount::mount2(None::<&str>, home, Some("tmpfs"), MountFlags::empty(), None)?;
+            None
+        },
+    };
 
     // Try to copy X.Org files.
     let _ = fs::copy(write_home.join(".Xauthority"), home.join(".Xauthority"));
 
+    Ok(crypt)
+}
+
+/// Add or remove keyslots on a LUKS-backed home, or print their status.
+///
+/// Opens `crypt_dir` via [`luks::Crypt::open_with_keyfile`], trying
+/// `--keyfile` before falling back to an interactive prompt, and authorizes
+/// any requested changes with the same passphrase.
+fn manage_keyslots(crypt_dir: &Path, args: &Args) -> Result<(), Error> {
+    let keyfile = args.keyfile.as_deref();
+    let unlock = Secret::read("Password: ", "HOMESEC_PASSWORD", keyfile)?;
+    let crypt = luks::Crypt::open_with_keyfile(crypt_dir, keyfile, &unlock)?;
+
+    if let Some(new_keyfile) = &args.add_keyfile {
+        let slot = crypt.add_keyfile(&unlock, new_keyfile)?;
+        println!("Added keyfile to slot {slot}");
+    }
+
+    if let Some(slot) = args.remove_keyslot {
+        crypt.remove_keyslot(slot)?;
+        println!("Removed slot {slot}");
+    }
+
+    if args.list_keyslots {
+        for info in crypt.list_keyslots() {
+            println!("slot {}: {}", info.slot, if info.active { "active" } else { "empty" });
+        }
+    }
+
     Ok(())
 }
 
+/// Unlock and mount an existing encrypted block device inside the sandbox.
+///
+/// `selector` may be either the device's filesystem UUID or its path (e.g.
+/// `/dev/sdb1`). The returned [`blockdev::AttachedDevice`] must be kept alive
+/// for as long as the device should stay mounted; dropping it tears the
+/// mount down.
+fn attach_device(selector: &str, read_only: bool) -> Result<blockdev::AttachedDevice, Error> {
+    let device = blockdev::find(selector)?
+        .ok_or_else(|| io::Error::other(format!("No block device matches '{selector}'")))?;
+
+    let passphrase = Secret::read("Attached device password: ", "HOMESEC_ATTACH_PASSWORD", None)?;
+    let mut attached = blockdev::AttachedDevice::unlock(&device, &passphrase)?;
+
+    let name = device.name.file_name().unwrap_or_default();
+    let mount_path = Path::new(ATTACH_ROOT).join(name);
+
+    // The new root is read-only; create the mount point through the writable
+    // old root first, same as `create_home`'s `write_crypt_dir`.
+    fs::create_dir_all(join_absolute_paths(WRITE_ROOT, &mount_path))?;
+    attached.mount(mount_path, read_only)?;
+
+    Ok(attached)
+}
+
 /// Combine two absolute paths.
 ///
 /// This combines the `root` with `path` pretending that `path` starts with `./`
@@ -158,30 +403,17 @@ fn join_absolute_paths(root: impl Into<PathBuf>, path: impl AsRef<Path>) -> Path
     joined
 }
 
-/// Read a password from STDIN.
-fn read_password() -> io::Result<String> {
-    // Prompt for password.
-    print!("Password: ");
-    io::stdout().flush()?;
-
-    // Get current terminal config.
-    let tty = File::open("/dev/tty")?;
-    let mut termios = termios::tcgetattr(&tty)?;
-
-    // Stop write-back of user input.
-    termios.local_modes.remove(LocalModes::ECHO);
-    termios.local_modes.insert(LocalModes::ECHONL);
-    termios::tcsetattr(&tty, OptionalActions::Now, &termios)?;
-
-    // Read the password.
-    let reader = BufReader::new(&tty);
-    let line =
-        reader.lines().next().ok_or_else(|| io::Error::other("Failed to read password from STDIN"));
-
-    // Reset terminal modes.
-    termios.local_modes.remove(LocalModes::ECHONL);
-    termios.local_modes.insert(LocalModes::ECHO);
-    termios::tcsetattr(&tty, OptionalActions::Now, &termios)?;
-
-    line?
+/// Top-level application error.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Errno(#[from] rustix::io::Errno),
+    #[error(transparent)]
+    Luks(#[from] luks::Error),
+    #[error(transparent)]
+    Blockdev(#[from] blockdev::Error),
+    #[error(transparent)]
+    Nul(#[from] std::ffi::NulError),
 }
@@ -4,8 +4,9 @@
 // https://github.com/phylum-dev/birdcage/blob/main/src/linux/namespaces.rs
 
 use std::path::{Path, PathBuf};
-use std::{env, fs, io};
+use std::{env, fs, io, process};
 
+use rustix::mount::{self, MountFlags};
 use rustix::thread::{self, Gid, Uid, UnshareFlags};
 
 /// Change root directory to `new_root` and mount the old root in `put_old`.
@@ -49,6 +50,89 @@ pub fn create_user_namespace(
     Ok(())
 }
 
+/// Run `f` isolated inside a fresh PID namespace, with its own private
+/// `/proc`.
+///
+/// `unshare(CLONE_NEWPID)` only affects processes created after the call; the
+/// calling process itself stays in the old namespace. We therefore `fork`,
+/// and the child becomes PID 1 of the new namespace. That child mounts a
+/// fresh `/proc` (so `ps` and friends only see the sandbox) and forks again
+/// to run `f`, since PID 1 is responsible for reaping orphaned descendants
+/// and can't spend its life blocked inside `f`. The original process just
+/// waits for PID 1 to exit and forwards its exit code.
+pub fn run_in_pid_namespace(f: impl FnOnce() -> i32) -> io::Result<i32> {
+    thread::unshare(UnshareFlags::NEWPID)?;
+
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        // PID 1 of the new namespace.
+        0 => {
+            if let Err(err) = mount_fresh_proc() {
+                eprintln!("[ERROR] Failed to mount fresh /proc: {err}");
+                process::exit(255);
+            }
+
+            match unsafe { libc::fork() } {
+                -1 => process::exit(255),
+                0 => process::exit(f()),
+                child => reap_until_exit(child),
+            }
+        },
+        pid => wait_for_exit(pid),
+    }
+}
+
+/// Shadow the inherited `/proc` with a fresh mount, so it only reflects the
+/// processes inside this PID namespace.
+///
+/// This must happen before the mount namespace is made readonly again, since
+/// mounting a new filesystem requires write access to its parent mount.
+fn mount_fresh_proc() -> io::Result<()> {
+    mount::mount2(None::<&str>, "/proc", Some("proc"), MountFlags::empty(), None)?;
+    Ok(())
+}
+
+/// Reap every exited child, forwarding `target`'s exit code once it exits.
+///
+/// As PID 1 of its namespace, this process inherits orphaned descendants of
+/// anything `f` spawned; those must be reaped or they pile up as zombies.
+fn reap_until_exit(target: libc::pid_t) -> ! {
+    loop {
+        let mut status = 0;
+        let pid = unsafe { libc::waitpid(-1, &mut status, 0) };
+
+        if pid == target {
+            process::exit(exit_code(status));
+        } else if pid == -1 {
+            // No children left to reap; `target` must already be one of them.
+            process::exit(0);
+        }
+    }
+}
+
+/// Wait for `pid` to exit and return its exit code.
+fn wait_for_exit(pid: libc::pid_t) -> io::Result<i32> {
+    loop {
+        let mut status = 0;
+        let result = unsafe { libc::waitpid(pid, &mut status, 0) };
+
+        if result == pid {
+            return Ok(exit_code(status));
+        } else if result == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+}
+
+/// Convert a `waitpid` status into a process exit code.
+fn exit_code(status: i32) -> i32 {
+    if libc::WIFEXITED(status) {
+        libc::WEXITSTATUS(status)
+    } else {
+        128 + libc::WTERMSIG(status)
+    }
+}
+
 /// Update /proc uid/gid maps.
 ///
 /// This should be called after creating a user namespace to ensure proper ID
@@ -0,0 +1,113 @@
+//! Secret handling.
+//!
+//! Passphrases are held in buffers that are zeroed on drop instead of plain
+//! `String`s, and the encryption passphrase is kept separate from any
+//! privilege-escalation (e.g. `sudo`) password so a leak of one can't be
+//! mistaken for the other.
+
+use std::env;
+use std::ffi::CStr;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use rustix::termios::{self, LocalModes, OptionalActions};
+use zeroize::Zeroizing;
+
+/// A single secret, held in a buffer that is zeroed on drop.
+///
+/// The buffer is kept NUL-terminated internally so [`Secret::as_cstr`] can
+/// hand out a borrowed `&CStr` without materializing a second, non-zeroizing
+/// copy at every FFI call site.
+pub struct Secret(Zeroizing<Vec<u8>>);
+
+impl Secret {
+    /// Source a secret, trying (in order) `env_var`, `keyfile`, then an
+    /// interactive TTY prompt.
+    pub fn read(prompt: &str, env_var: &str, keyfile: Option<&Path>) -> io::Result<Self> {
+        if let Ok(value) = env::var(env_var) {
+            return Self::from_bytes(value.into_bytes());
+        }
+
+        if let Some(keyfile) = keyfile {
+            return Self::from_bytes(fs::read(keyfile)?);
+        }
+
+        Self::read_tty(prompt)
+    }
+
+    /// Prompt for a secret on `/dev/tty`, with terminal echo disabled.
+    fn read_tty(prompt: &str) -> io::Result<Self> {
+        print!("{prompt}");
+        io::stdout().flush()?;
+
+        // Get current terminal config.
+        let tty = File::open("/dev/tty")?;
+        let mut termios = termios::tcgetattr(&tty)?;
+
+        // Stop write-back of user input.
+        termios.local_modes.remove(LocalModes::ECHO);
+        termios.local_modes.insert(LocalModes::ECHONL);
+        termios::tcsetattr(&tty, OptionalActions::Now, &termios)?;
+
+        // Read the secret.
+        let reader = BufReader::new(&tty);
+        let line =
+            reader.lines().next().ok_or_else(|| io::Error::other("Failed to read secret from STDIN"));
+
+        // Reset terminal modes.
+        termios.local_modes.remove(LocalModes::ECHONL);
+        termios.local_modes.insert(LocalModes::ECHO);
+        termios::tcsetattr(&tty, OptionalActions::Now, &termios)?;
+
+        Self::from_bytes(line?.into_bytes())
+    }
+
+    /// Store `bytes` in a zeroizing buffer with a trailing NUL appended.
+    fn from_bytes(mut bytes: Vec<u8>) -> io::Result<Self> {
+        if bytes.contains(&0) {
+            return Err(io::Error::other("secret contains an embedded NUL byte"));
+        }
+
+        bytes.push(0);
+        Ok(Self(Zeroizing::new(bytes)))
+    }
+
+    /// Borrow the secret's raw bytes (without the trailing NUL), for call
+    /// sites taking key material directly (e.g. scrypt, HKDF).
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0[..self.0.len() - 1]
+    }
+
+    /// Borrow the secret as a NUL-terminated C string, for FFI call sites
+    /// that need a `&CStr` (e.g. libcryptsetup).
+    pub fn as_cstr(&self) -> &CStr {
+        CStr::from_bytes_with_nul(&self.0)
+            .expect("Secret is always stored with exactly one, trailing NUL byte")
+    }
+}
+
+/// The distinct secrets used across the sandboxing pipeline.
+pub struct PasswordHolder {
+    /// Password protecting the encrypted home (gocryptfs or LUKS).
+    pub encryption: Secret,
+    /// Password for privilege escalation, if the current operation needs one.
+    pub sudo: Option<Secret>,
+}
+
+impl PasswordHolder {
+    /// Source the encryption passphrase, optionally from `keyfile`.
+    ///
+    /// A sudo password is only sourced when `need_sudo` is set, so operations
+    /// that never escalate privileges don't force an interactive prompt; when
+    /// it is needed, it goes through the same env var/keyfile/TTY precedence
+    /// as the encryption passphrase, via `sudo_keyfile`.
+    pub fn new(keyfile: Option<&Path>, need_sudo: bool, sudo_keyfile: Option<&Path>) -> io::Result<Self> {
+        let encryption = Secret::read("Password: ", "HOMESEC_PASSWORD", keyfile)?;
+        let sudo = need_sudo
+            .then(|| Secret::read("Sudo password: ", "HOMESEC_SUDO_PASSWORD", sudo_keyfile))
+            .transpose()?;
+
+        Ok(Self { encryption, sudo })
+    }
+}